@@ -1,18 +1,27 @@
 /// Taken from the `niri_ipc` crate.
 /// Only a relevant snippet has been extracted
 /// to reduce compile times.
-use crate::await_sync;
+use crate::{await_sync, spawn};
 use std::env;
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
 use std::path::Path;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
+use tokio::sync::{mpsc, oneshot};
+use tracing::debug;
 
 // Re-export types from the crate
-pub use niri_ipc::{Action, Event, Reply, Request, Response, Window, Workspace};
+pub use niri_ipc::{
+    Action, Event, Reply, Request, Response, Window, Workspace, WorkspaceReferenceArg,
+};
 
+// The `BufReader` is kept as part of the connection's persistent state rather than
+// rebuilt per call - it's only ever safe to drop and recreate because nothing is left
+// buffered at the point it happens to be rebuilt, which is an invariant the old code
+// relied on accidentally rather than upheld. Keeping it alongside the socket means no
+// future pipelining or coalesced replies can lose bytes left over from a previous read.
 #[derive(Debug)]
-pub struct Connection(UnixStream);
+pub struct Connection(BufReader<UnixStream>);
 
 impl Connection {
     pub async fn connect() -> Result<Self> {
@@ -24,21 +33,20 @@ impl Connection {
 
     pub async fn connect_to(path: impl AsRef<Path>) -> Result<Self> {
         let raw_stream = UnixStream::connect(path.as_ref()).await?;
-        Ok(Self(raw_stream))
+        Ok(Self(BufReader::new(raw_stream)))
     }
 
     pub async fn send(
         &mut self,
         request: Request,
     ) -> Result<(Reply, impl FnMut() -> Result<Event> + '_)> {
-        let Self(stream) = self;
+        let Self(reader) = self;
         let mut buf = serde_json::to_string(&request)?;
 
-        stream.write_all(buf.as_bytes()).await?;
-        stream.shutdown().await?;
+        reader.get_mut().write_all(buf.as_bytes()).await?;
+        reader.get_mut().shutdown().await?;
 
         buf.clear();
-        let mut reader = BufReader::new(stream);
         reader.read_line(&mut buf).await?;
         let reply = serde_json::from_str(&buf)?;
 
@@ -51,10 +59,94 @@ impl Connection {
             if buf.trim().is_empty() {
                 return Ok(Event::Other); // Treat as no-op
             }
-            let event: Event = serde_json::from_str(&buf).unwrap_or(Event::Other);
-            Ok(event)
+            // A successfully parsed `Event::Other` is a *recognised* Niri event we just
+            // don't act on (overview, keyboard layout, ...) and should
+            // be a no-op. A genuine parse failure is a different problem - a dropped or
+            // malformed message - and is surfaced as an `Err` instead of being folded
+            // into the same variant, so callers can tell "ignore this" apart from
+            // "resync, something went wrong".
+            serde_json::from_str(&buf).map_err(|e| Error::new(ErrorKind::InvalidData, e))
         };
         Ok((reply, events))
     }
+
+    /// Sends a single request over an already-open connection and reads back its reply,
+    /// without half-closing the socket - so the connection can be reused for the next
+    /// request. Niri's socket protocol is newline-delimited JSON.
+    async fn send_keepalive(&mut self, request: Request) -> Result<Reply> {
+        let Self(reader) = self;
+        let mut buf = serde_json::to_string(&request)?;
+        buf.push('\n');
+
+        reader.get_mut().write_all(buf.as_bytes()).await?;
+        reader.get_mut().flush().await?;
+
+        buf.clear();
+        reader.read_line(&mut buf).await?;
+        Ok(serde_json::from_str(&buf)?)
+    }
 }
+
+type ActionCommand = (Request, oneshot::Sender<Result<Reply>>);
+
+/// A persistent connection dedicated to one-off requests such as `Action`s. Commands are
+/// funnelled through a queue and served by a background task that holds a single
+/// long-lived connection, so the Unix socket handshake cost is paid once rather than on
+/// every `focus`/move/etc. call. The command connection is kept separate from the
+/// event-stream connection. If the socket drops, it is transparently re-established.
+#[derive(Debug, Clone)]
+pub struct ActionQueue(mpsc::Sender<ActionCommand>);
+
+impl ActionQueue {
+    pub fn spawn() -> Self {
+        let (tx, rx) = mpsc::channel(32);
+        spawn(Self::run(rx));
+        Self(tx)
+    }
+
+    /// Sends a request through the persistent command connection and awaits its reply.
+    pub async fn send(&self, request: Request) -> Result<Reply> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+
+        self.0.send((request, resp_tx)).await.map_err(|_| {
+            Error::new(ErrorKind::BrokenPipe, "Niri action queue is closed")
+        })?;
+
+        resp_rx.await.map_err(|_| {
+            Error::new(ErrorKind::BrokenPipe, "Niri action queue dropped the response")
+        })?
+    }
+
+    async fn run(mut rx: mpsc::Receiver<ActionCommand>) -> Result<()> {
+        let mut conn: Option<Connection> = None;
+
+        while let Some((request, responder)) = rx.recv().await {
+            if conn.is_none() {
+                conn = Connection::connect().await.ok();
+            }
+
+            let result = match conn.as_mut() {
+                Some(c) => match c.send_keepalive(request.clone()).await {
+                    Ok(reply) => Ok(reply),
+                    Err(err) => {
+                        debug!("Niri command connection dropped, reconnecting: {err}");
+                        conn = None;
+                        match Connection::connect().await {
+                            Ok(mut new_conn) => {
+                                let retried = new_conn.send_keepalive(request).await;
+                                conn = Some(new_conn);
+                                retried
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                },
+                None => Err(Error::new(ErrorKind::NotConnected, "not connected to Niri")),
+            };
+
+            let _ = responder.send(result);
+        }
+
+        Ok(())
+    }
 }
@@ -2,218 +2,1000 @@ use super::{Workspace as IronWorkspace, WorkspaceClient, WorkspaceUpdate};
 use crate::channels::SyncSenderExt;
 use crate::clients::compositor::Visibility;
 use crate::{arc_rw, read_lock, spawn, write_lock};
-use connection::{Action, Connection, Event, Request, Response, Window};
+use connection::{
+    Action, ActionQueue, Connection, Event, Request, Response, Window, Workspace,
+    WorkspaceReferenceArg,
+};
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 use tokio::sync::broadcast;
 use tracing::{debug, error};
 
 mod connection;
 
+/// Controls how the Niri backend derives Ironbar workspaces.
+///
+/// Deserializable so it can be read straight out of config once the Niri backend is
+/// wired into the module where `Client::new` is actually called - that module
+/// (`compositor/mod.rs`) isn't present in this checkout yet, so there's no config
+/// struct to add a field to here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceMode {
+    /// Mirror Niri's actual workspaces, like every other compositor backend.
+    #[default]
+    Workspaces,
+    /// Legacy behaviour: map each window to a synthetic workspace.
+    Windows,
+}
+
+/// Controls the order in which workspace/window buttons are presented.
+///
+/// Deserializable for the same reason as `WorkspaceMode` - see there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortMode {
+    /// Plain index order, as reported by Niri.
+    #[default]
+    Index,
+    /// Urgent first, then least-recently-used, with the currently focused item last -
+    /// the ordering scheme swayr uses for its switcher.
+    Mru,
+}
+
+/// Maps a native Niri `Workspace` to an `IronWorkspace`, using its stable id, per-monitor
+/// index, optional name and focus/activity flags directly.
+fn workspace_to_ironworkspace(workspace: &Workspace) -> IronWorkspace {
+    let name = workspace
+        .name
+        .clone()
+        .unwrap_or_else(|| workspace.idx.to_string());
+
+    IronWorkspace {
+        id: workspace.id as i64,
+        index: workspace.idx as i64,
+        name,
+        monitor: workspace.output.clone().unwrap_or_default(),
+        visibility: if workspace.is_focused {
+            Visibility::focused()
+        } else if workspace.is_active {
+            Visibility::visible()
+        } else {
+            Visibility::hidden()
+        },
+    }
+}
+
+/// In-memory mirror of the window/workspace state Niri reports, kept up to date by
+/// applying events in place rather than re-fetching a full snapshot on every change.
+#[derive(Debug, Default)]
+struct State {
+    windows: HashMap<u64, Window>,
+    workspaces: HashMap<u64, Workspace>,
+    focused_window: Option<u64>,
+
+    /// When each id (window id, or workspace id in native mode) was last focused -
+    /// the basis for `SortMode::Mru`.
+    last_focused: HashMap<u64, Instant>,
+}
+
+impl State {
+    /// Looks up the workspace a window belongs to, if known.
+    fn workspace_for(&self, window: &Window) -> Option<&Workspace> {
+        window.workspace_id.and_then(|id| self.workspaces.get(&id))
+    }
+
+    /// Maps a window to an `IronWorkspace`, resolving its index/monitor via the
+    /// workspace it currently lives on.
+    fn build_window(&self, window: &Window) -> IronWorkspace {
+        let index = self.workspace_for(window).map_or(0, |ws| ws.idx as i64);
+        let monitor = self
+            .workspace_for(window)
+            .and_then(|ws| ws.output.clone())
+            .or_else(|| window.output.clone())
+            .unwrap_or_default();
+
+        // We map the window ID to the workspace ID so buttons track windows.
+        // We use the window title (or app_id if title is empty) as the name.
+        let name = window
+            .title
+            .clone()
+            .or_else(|| window.app_id.clone())
+            .unwrap_or_else(|| "Window".to_string());
+
+        // A window-list button still needs to show which workspace it's on when that
+        // workspace is named - otherwise a named/special workspace is only addressable
+        // in native workspace mode, not here, even though both modes share the same
+        // `focus_named_workspace` plumbing.
+        let name = match self.workspace_for(window).and_then(|ws| ws.name.clone()) {
+            Some(ws_name) => format!("{ws_name}: {name}"),
+            None => name,
+        };
+
+        IronWorkspace {
+            id: window.id as i64,
+            index,
+            name,
+            monitor,
+            visibility: if self.focused_window == Some(window.id) {
+                Visibility::focused()
+            } else {
+                Visibility::visible()
+            },
+        }
+    }
+
+    /// All windows, mapped to `IronWorkspace`s and ordered per `sort`.
+    fn windows_ordered(&self, sort: SortMode) -> Vec<IronWorkspace> {
+        let mut workspaces: Vec<IronWorkspace> =
+            self.windows.values().map(|w| self.build_window(w)).collect();
+        let urgent = self.windows.values().filter(|w| w.is_urgent).map(|w| w.id);
+        self.sort(&mut workspaces, sort, urgent);
+
+        // Unlike native workspace mode, where Niri's per-monitor `idx` is already
+        // unique, `build_window` borrows that same shared `idx` here, and several
+        // windows can live on one workspace - so every button would collide on the
+        // same `index` if it were left alone. Rewrite to a unique sequential rank in
+        // sorted order instead - the same thing `SortMode::Mru` already does for its
+        // own ranking inside `sort` - so "place buttons by index" holds under every
+        // sort mode.
+        reindex(&mut workspaces);
+
+        workspaces
+    }
+
+    /// All native workspaces, mapped to `IronWorkspace`s and ordered per `sort`.
+    fn workspaces_ordered(&self, sort: SortMode) -> Vec<IronWorkspace> {
+        let mut workspaces: Vec<IronWorkspace> = self
+            .workspaces
+            .values()
+            .map(workspace_to_ironworkspace)
+            .collect();
+        let urgent = self.workspaces.values().filter(|w| w.is_urgent).map(|w| w.id);
+        self.sort(&mut workspaces, sort, urgent);
+        workspaces
+    }
+
+    fn ordered(&self, mode: WorkspaceMode, sort: SortMode) -> Vec<IronWorkspace> {
+        match mode {
+            WorkspaceMode::Windows => self.windows_ordered(sort),
+            WorkspaceMode::Workspaces => self.workspaces_ordered(sort),
+        }
+    }
+
+    /// Sorts `workspaces` in place. In `SortMode::Index` this is just (index, id), as
+    /// reported by Niri. In `SortMode::Mru`, urgent items come first, then the rest in
+    /// least-recently-used order, with the focused item pinned last; the resulting
+    /// position is written back into each `IronWorkspace`'s `index` so downstream
+    /// widgets - which place buttons by `index` - pick it up automatically.
+    fn sort(&self, workspaces: &mut [IronWorkspace], sort: SortMode, urgent: impl Iterator<Item = u64>) {
+        match sort {
+            SortMode::Index => workspaces.sort_by_key(|w| (w.index, w.id)),
+            SortMode::Mru => {
+                let urgent: std::collections::HashSet<u64> = urgent.collect();
+                workspaces.sort_by_key(|w| {
+                    let id = w.id as u64;
+                    let group = if w.visibility.is_focused() {
+                        2
+                    } else if urgent.contains(&id) {
+                        0
+                    } else {
+                        1
+                    };
+                    (group, self.last_focused.get(&id).copied(), w.id)
+                });
+                reindex(workspaces);
+            }
+        }
+    }
+
+    /// Records that `id` was just focused, for `SortMode::Mru`.
+    fn touch_focus(&mut self, id: u64) {
+        self.last_focused.insert(id, Instant::now());
+    }
+
+    /// Drops any `last_focused` entry whose window/workspace no longer exists, so a
+    /// full resync doesn't leave stale MRU timestamps behind forever.
+    fn prune_last_focused(&mut self) {
+        let live_ids: std::collections::HashSet<u64> = self
+            .workspaces
+            .keys()
+            .chain(self.windows.keys())
+            .copied()
+            .collect();
+        self.last_focused.retain(|id, _| live_ids.contains(id));
+    }
+}
+
+/// Rewrites each `IronWorkspace`'s `index` to its position in `workspaces`, so "place
+/// buttons by index" holds even when the value `index` started out with (e.g. a shared
+/// workspace `idx` for several windows) wasn't already a unique rank.
+fn reindex(workspaces: &mut [IronWorkspace]) {
+    for (rank, w) in workspaces.iter_mut().enumerate() {
+        w.index = rank as i64;
+    }
+}
+
+/// Diffs two already-ordered `IronWorkspace` snapshots and emits the Add/Rename/Move/
+/// Remove/Focus updates needed to bring subscribers from `old` to `new`.
+fn diff_and_emit(tx: &broadcast::Sender<WorkspaceUpdate>, old: &[IronWorkspace], new: &[IronWorkspace]) {
+    for new_w in new {
+        if let Some(old_w) = old.iter().find(|w| w.id == new_w.id) {
+            if new_w.name != old_w.name {
+                tx.send_expect(WorkspaceUpdate::Rename {
+                    id: new_w.id,
+                    name: new_w.name.clone(),
+                });
+            }
+            let lost_focus = old_w.visibility.is_focused() && !new_w.visibility.is_focused();
+            if new_w.index != old_w.index || new_w.monitor != old_w.monitor || lost_focus {
+                // `WorkspaceUpdate::Focus` can't express "no new focus", so a plain
+                // focus loss (e.g. switching to an empty workspace) is surfaced as a
+                // Move of the now-unfocused entry instead of being dropped silently.
+                tx.send_expect(WorkspaceUpdate::Move(new_w.clone()));
+            }
+            if new_w.visibility.is_focused() && !old_w.visibility.is_focused() {
+                tx.send_expect(WorkspaceUpdate::Focus {
+                    old: Some(old_w.clone()),
+                    new: new_w.clone(),
+                });
+            }
+        } else {
+            tx.send_expect(WorkspaceUpdate::Add(new_w.clone()));
+        }
+    }
+
+    for old_w in old {
+        if !new.iter().any(|w| w.id == old_w.id) {
+            tx.send_expect(WorkspaceUpdate::Remove(old_w.id));
+        }
+    }
+}
+
+/// Computes which workspace ids have an `is_active`/`is_focused` flag about to flip when
+/// `id` is activated: `id` itself, any sibling on the same output (about to be
+/// deactivated), and the previously focused workspace if focus is moving. Takes plain
+/// tuples rather than `Workspace` so the non-focused, secondary-output-activation case
+/// can be tested without constructing a native Niri workspace.
+fn activation_changed_ids(
+    workspaces: impl Iterator<Item = (u64, Option<String>, bool, bool)>,
+    id: u64,
+    output: &Option<String>,
+    focused: bool,
+) -> Vec<u64> {
+    let mut changed_ids: Vec<u64> = workspaces
+        .filter(|(ws_id, ws_output, is_active, is_focused)| {
+            *ws_id == id || (ws_output == output && *is_active) || (focused && *is_focused)
+        })
+        .map(|(ws_id, ..)| ws_id)
+        .collect();
+    changed_ids.sort_unstable();
+    changed_ids.dedup();
+    changed_ids
+}
+
 #[derive(Debug)]
 pub struct Client {
     tx: broadcast::Sender<WorkspaceUpdate>,
     _rx: broadcast::Receiver<WorkspaceUpdate>,
 
-    // We store "windows" in the "workspaces" state variable
-    // because we are mapping windows -> IronWorkspace
-    windows_as_workspaces: Arc<RwLock<Vec<IronWorkspace>>>,
+    mode: WorkspaceMode,
+    sort: SortMode,
+    state: Arc<RwLock<State>>,
+    actions: ActionQueue,
 }
 
 impl Client {
-    pub fn new() -> Self {
+    pub fn new(mode: WorkspaceMode, sort: SortMode) -> Self {
         let (tx, rx) = broadcast::channel(32);
         let tx2 = tx.clone();
 
-        let window_state = arc_rw!(vec![]);
-        let window_state2 = window_state.clone();
+        let state = arc_rw!(State::default());
+        let state2 = state.clone();
+        let actions = ActionQueue::spawn();
 
         spawn(async move {
-            let mut conn = Connection::connect().await?;
-            let (_, mut event_listener) = conn.send(Request::EventStream).await?;
-
-            // Initial fetch
-            Self::refresh_windows(&tx, &window_state, true).await;
-
-            loop {
-                // We just listen for events. If *anything* relevant changes, we fetch the full window list.
-                // This is robust against drift and handles the sorting automatically since Niri
-                // returns windows in order.
-                let event = match event_listener() {
-                    Ok(event) => event,
+            // Outer loop: (re)establish the event-stream connection and reseed from
+            // scratch, then process events until something goes wrong. A bad connect or
+            // a deserialize failure on the stream both land here via `continue
+            // 'reconnect`; an unrecognised-but-valid event does not - see `Event::Other`
+            // below.
+            'reconnect: loop {
+                let mut conn = match Connection::connect().await {
+                    Ok(conn) => conn,
                     Err(err) => {
-                        error!("Niri connection error: {err:?}");
-                        break;
+                        error!("Failed to connect to Niri for event stream: {err}");
+                        continue 'reconnect;
                     }
                 };
+                let (_, mut event_listener) = match conn.send(Request::EventStream).await {
+                    Ok(pair) => pair,
+                    Err(err) => {
+                        error!("Failed to start Niri event stream: {err}");
+                        continue 'reconnect;
+                    }
+                };
+
+                // Seed the in-memory state once per (re)connect, then keep it up to date
+                // below by mutating it in place as fine-grained events arrive.
+                Self::seed(&tx, &state, mode, sort).await;
+
+                loop {
+                    let event = match event_listener() {
+                        Ok(event) => event,
+                        Err(err) => {
+                            // A genuine deserialize failure (dropped/malformed message) -
+                            // unlike a recognised-but-unhandled event, this means the
+                            // stream itself can no longer be trusted, so reconnect and
+                            // reseed.
+                            error!("Niri event stream error, reconnecting: {err:?}");
+                            continue 'reconnect;
+                        }
+                    };
 
-                let should_refresh = matches!(
-                    event,
-                    Event::WindowsChanged { .. }
-                        | Event::WindowOpenedOrChanged { .. }
-                        | Event::WindowClosed { .. }
-                        | Event::WindowFocusChanged { .. }
-                        | Event::WorkspacesChanged { .. }
-                        | Event::WorkspaceActivated { .. }
-                );
-
-                if should_refresh {
-                    Self::refresh_windows(&tx, &window_state, false).await;
+                    match event {
+                        Event::WindowOpenedOrChanged { window } => {
+                            Self::apply_window_opened_or_changed(&tx, &state, mode, sort, window);
+                        }
+                        Event::WindowClosed { id } => {
+                            Self::apply_window_closed(&tx, &state, mode, sort, id);
+                        }
+                        Event::WindowFocusChanged { id } => {
+                            Self::apply_window_focus_changed(&tx, &state, mode, sort, id);
+                        }
+                        Event::WorkspaceActivated { id, focused } => {
+                            Self::apply_workspace_activated(&tx, &state, mode, sort, id, focused);
+                        }
+                        Event::WorkspacesChanged { workspaces } => {
+                            Self::apply_workspaces_changed(&tx, &state, mode, sort, workspaces);
+                        }
+                        Event::WindowsChanged { windows } => {
+                            // This is Niri's full snapshot event - resync against its
+                            // payload directly, without paying for a reconnect.
+                            Self::apply_windows_changed(&tx, &state, mode, sort, windows);
+                        }
+                        Event::WindowUrgencyChanged { id, urgent } => {
+                            Self::apply_window_urgency_changed(&tx, &state, mode, sort, id, urgent);
+                        }
+                        Event::WorkspaceUrgencyChanged { id, urgent } => {
+                            Self::apply_workspace_urgency_changed(&tx, &state, mode, sort, id, urgent);
+                        }
+                        Event::Other => {
+                            // A recognised Niri event with no handling here (overview,
+                            // keyboard layout, ...) - a genuine no-op,
+                            // not a reason to reconnect and blow away every button's
+                            // state with a fresh `Init`.
+                        }
+                    }
                 }
             }
-
-            Ok::<(), std::io::Error>(())
         });
 
         Self {
             tx: tx2,
             _rx: rx,
-            windows_as_workspaces: window_state2,
+            mode,
+            sort,
+            state: state2,
+            actions,
         }
     }
 
-    /// Fetches the list of windows from Niri and updates the Ironbar state.
-    async fn refresh_windows(
+    /// Fetches the full window and workspace lists once, populates the state from
+    /// scratch, and emits an `Init` update.
+    async fn seed(
         tx: &broadcast::Sender<WorkspaceUpdate>,
-        state_lock: &Arc<RwLock<Vec<IronWorkspace>>>,
-        is_init: bool,
+        state_lock: &Arc<RwLock<State>>,
+        mode: WorkspaceMode,
+        sort: SortMode,
     ) {
-        // We need a separate connection to send requests while the other is listening
-        let windows = match Connection::connect().await {
-            Ok(mut conn) => match conn.send(Request::Windows).await {
-                Ok((Ok(Response::Windows(windows)), _)) => windows,
-                Ok((Err(e), _)) => {
-                    error!("Failed to fetch windows: {e}");
-                    return;
-                }
+        async fn connect(context: &str) -> Option<Connection> {
+            match Connection::connect().await {
+                Ok(conn) => Some(conn),
                 Err(e) => {
-                    error!("Failed to send window request: {e}");
-                    return;
+                    error!("Failed to connect to Niri for {context}: {e}");
+                    None
                 }
-                _ => return,
-            },
+            }
+        }
+
+        let Some(mut conn) = connect("initial window fetch").await else {
+            return;
+        };
+
+        let windows = match conn.send(Request::Windows).await {
+            Ok((Ok(Response::Windows(windows)), _)) => windows,
+            Ok((Err(e), _)) => {
+                error!("Failed to fetch windows: {e}");
+                return;
+            }
+            Err(e) => {
+                error!("Failed to send window request: {e}");
+                return;
+            }
+            _ => return,
+        };
+
+        // `Connection::send` shuts down the socket's write half as an EOF marker once
+        // it's written a request, so a connection is good for exactly one request - the
+        // workspaces fetch needs a fresh one rather than reusing `conn`.
+        let Some(mut conn) = connect("initial workspace fetch").await else {
+            return;
+        };
+
+        let workspaces = match conn.send(Request::Workspaces).await {
+            Ok((Ok(Response::Workspaces(workspaces)), _)) => workspaces,
+            Ok((Err(e), _)) => {
+                error!("Failed to fetch workspaces: {e}");
+                return;
+            }
             Err(e) => {
-                error!("Failed to connect to Niri for refresh: {e}");
+                error!("Failed to send workspaces request: {e}");
                 return;
             }
+            _ => return,
         };
 
-        // Convert Niri Windows to IronWorkspaces
-        // Niri returns windows in the correct order (workspace index, then id)
-        let new_workspaces: Vec<IronWorkspace> = windows
+        let mut state = write_lock!(state_lock);
+        state.focused_window = windows.iter().find(|w| w.is_focused).map(|w| w.id);
+        state.windows = windows.into_iter().map(|w| (w.id, w)).collect();
+        state.workspaces = workspaces.into_iter().map(|w| (w.id, w)).collect();
+
+        tx.send_expect(WorkspaceUpdate::Init(state.ordered(mode, sort)));
+    }
+
+    fn apply_window_opened_or_changed(
+        tx: &broadcast::Sender<WorkspaceUpdate>,
+        state_lock: &Arc<RwLock<State>>,
+        mode: WorkspaceMode,
+        sort: SortMode,
+        window: Window,
+    ) {
+        let mut state = write_lock!(state_lock);
+
+        if mode != WorkspaceMode::Windows {
+            state.windows.insert(window.id, window);
+            return;
+        }
+
+        // `windows_ordered` assigns each button's `index` as a unique rank over the
+        // whole list, not Niri's shared per-workspace `idx` (see there), so a single
+        // inserted/changed window can shift every other window's index too - there's
+        // no correct "just this one" shortcut left once that has to hold under every
+        // sort mode, not only `SortMode::Mru`.
+        let old = state.windows_ordered(sort);
+        state.windows.insert(window.id, window);
+        let new = state.windows_ordered(sort);
+        diff_and_emit(tx, &old, &new);
+    }
+
+    fn apply_window_closed(
+        tx: &broadcast::Sender<WorkspaceUpdate>,
+        state_lock: &Arc<RwLock<State>>,
+        mode: WorkspaceMode,
+        sort: SortMode,
+        id: u64,
+    ) {
+        let mut state = write_lock!(state_lock);
+
+        if mode != WorkspaceMode::Windows {
+            state.windows.remove(&id);
+            state.last_focused.remove(&id);
+            if state.focused_window == Some(id) {
+                state.focused_window = None;
+            }
+            return;
+        }
+
+        let old = state.windows_ordered(sort);
+        state.windows.remove(&id);
+        state.last_focused.remove(&id);
+        if state.focused_window == Some(id) {
+            state.focused_window = None;
+        }
+        let new = state.windows_ordered(sort);
+        diff_and_emit(tx, &old, &new);
+    }
+
+    fn apply_window_focus_changed(
+        tx: &broadcast::Sender<WorkspaceUpdate>,
+        state_lock: &Arc<RwLock<State>>,
+        mode: WorkspaceMode,
+        sort: SortMode,
+        id: Option<u64>,
+    ) {
+        let mut state = write_lock!(state_lock);
+
+        // The position of both the old and new focus holder can shift - under
+        // `SortMode::Mru` because focus directly drives the ranking, and under
+        // `SortMode::Index` because `windows_ordered` assigns `index` as a unique rank
+        // over the whole list (see there) - so capture the "before" order up front and
+        // diff it against the "after" order once mutated, rather than building just the
+        // two affected windows in isolation.
+        let old_ordered = (mode == WorkspaceMode::Windows).then(|| state.windows_ordered(sort));
+
+        state.focused_window = id;
+        if let Some(id) = id {
+            // Window ids and workspace ids are independent id-spaces that can collide,
+            // so only touch the MRU map when it's actually tracking windows here.
+            if mode == WorkspaceMode::Windows {
+                state.touch_focus(id);
+            }
+        }
+
+        if let Some(old) = old_ordered {
+            let new = state.windows_ordered(sort);
+            diff_and_emit(tx, &old, &new);
+        }
+    }
+
+    fn apply_workspace_activated(
+        tx: &broadcast::Sender<WorkspaceUpdate>,
+        state_lock: &Arc<RwLock<State>>,
+        mode: WorkspaceMode,
+        sort: SortMode,
+        id: u64,
+        focused: bool,
+    ) {
+        let mut state = write_lock!(state_lock);
+        let old_ordered = (mode == WorkspaceMode::Workspaces && sort == SortMode::Mru)
+            .then(|| state.workspaces_ordered(sort));
+        let output = state.workspaces.get(&id).and_then(|w| w.output.clone());
+        let old_focused_id = state
+            .workspaces
             .iter()
-            .enumerate()
-            .map(|(idx, w)| Self::window_to_workspace(w, idx as i64))
-            .collect();
+            .find(|(_, w)| w.is_focused)
+            .map(|(id, _)| *id);
 
-        let mut updates: Vec<WorkspaceUpdate> = vec![];
+        let changed_ids = activation_changed_ids(
+            state
+                .workspaces
+                .iter()
+                .map(|(ws_id, ws)| (*ws_id, ws.output.clone(), ws.is_active, ws.is_focused)),
+            id,
+            &output,
+            focused,
+        );
 
-        if is_init {
-            updates.push(WorkspaceUpdate::Init(new_workspaces.clone()));
-        } else {
-            let old_state = read_lock!(state_lock);
-
-            // 1. Check for Add/Update/Move
-            for new_w in &new_workspaces {
-                if let Some(old_w) = old_state.iter().find(|w| w.id == new_w.id) {
-                    // Check rename
-                    if new_w.name != old_w.name {
-                        updates.push(WorkspaceUpdate::Rename {
-                            id: new_w.id,
-                            name: new_w.name.clone(),
-                        });
-                    }
-                    // Check move (index changed or monitor changed)
-                    if new_w.index != old_w.index || new_w.monitor != old_w.monitor {
-                        updates.push(WorkspaceUpdate::Move(new_w.clone()));
-                    }
-                    // Check focus change
-                    if new_w.visibility.is_focused() != old_w.visibility.is_focused() {
-                        // We handle focus update specifically
-                        if new_w.visibility.is_focused() {
-                             updates.push(WorkspaceUpdate::Focus {
-                                old: Some(old_w.clone()), // The previously known state of this window
-                                new: new_w.clone()
-                            });
-                        }
-                    }
-                } else {
-                    updates.push(WorkspaceUpdate::Add(new_w.clone()));
+        for (ws_id, ws) in &mut state.workspaces {
+            if *ws_id == id {
+                ws.is_active = true;
+                ws.is_focused = ws.is_focused || focused;
+            } else {
+                if ws.output == output {
+                    ws.is_active = false;
+                }
+                if focused {
+                    ws.is_focused = false;
                 }
             }
+        }
+        // Window ids and workspace ids are independent id-spaces that can collide, so
+        // only touch the MRU map when it's actually tracking workspaces here.
+        if focused && mode == WorkspaceMode::Workspaces {
+            state.touch_focus(id);
+        }
+
+        if mode != WorkspaceMode::Workspaces {
+            return;
+        }
+
+        if let Some(old) = old_ordered {
+            let new = state.workspaces_ordered(sort);
+            diff_and_emit(tx, &old, &new);
+            return;
+        }
+
+        // `SortMode::Index` doesn't need a full diff, but activating a workspace on a
+        // secondary output still changes `is_active`/`is_focused` flags even when
+        // `focused` is false, so every affected workspace needs an update - not only
+        // the one gaining keyboard focus, or the bar never reflects activation on
+        // non-focused monitors.
+        for changed_id in changed_ids {
+            let Some(new) = state.workspaces.get(&changed_id).map(workspace_to_ironworkspace)
+            else {
+                continue;
+            };
+            if changed_id == id && focused {
+                let old = old_focused_id
+                    .and_then(|id| state.workspaces.get(&id))
+                    .map(workspace_to_ironworkspace);
+                tx.send_expect(WorkspaceUpdate::Focus { old, new });
+            } else {
+                tx.send_expect(WorkspaceUpdate::Move(new));
+            }
+        }
+    }
+
+    fn apply_workspaces_changed(
+        tx: &broadcast::Sender<WorkspaceUpdate>,
+        state_lock: &Arc<RwLock<State>>,
+        mode: WorkspaceMode,
+        sort: SortMode,
+        workspaces: Vec<Workspace>,
+    ) {
+        let mut state = write_lock!(state_lock);
+        let old = (mode == WorkspaceMode::Workspaces).then(|| state.workspaces_ordered(sort));
+
+        state.workspaces = workspaces.into_iter().map(|w| (w.id, w)).collect();
+        state.prune_last_focused();
+
+        if let Some(old) = old {
+            let new = state.workspaces_ordered(sort);
+            diff_and_emit(tx, &old, &new);
+        }
+    }
+
+    fn apply_windows_changed(
+        tx: &broadcast::Sender<WorkspaceUpdate>,
+        state_lock: &Arc<RwLock<State>>,
+        mode: WorkspaceMode,
+        sort: SortMode,
+        windows: Vec<Window>,
+    ) {
+        let mut state = write_lock!(state_lock);
+        let old = (mode == WorkspaceMode::Windows).then(|| state.windows_ordered(sort));
+
+        state.focused_window = windows.iter().find(|w| w.is_focused).map(|w| w.id);
+        state.windows = windows.into_iter().map(|w| (w.id, w)).collect();
+        state.prune_last_focused();
+
+        if let Some(old) = old {
+            let new = state.windows_ordered(sort);
+            diff_and_emit(tx, &old, &new);
+        }
+    }
+
+    /// Flips a window's urgency flag in place and, under `SortMode::Mru`, re-sorts and
+    /// diff-emits the move this causes - urgency is part of the MRU ranking, so it can't
+    /// just wait for the next full resync.
+    /// Under `SortMode::Index` the ranking doesn't change, but the diff is still taken
+    /// through `windows_ordered` rather than a single `build_window` call, since `index`
+    /// there is a unique rank over the whole list (see `reindex`) and a lone window can't
+    /// recompute that in isolation.
+    fn apply_window_urgency_changed(
+        tx: &broadcast::Sender<WorkspaceUpdate>,
+        state_lock: &Arc<RwLock<State>>,
+        mode: WorkspaceMode,
+        sort: SortMode,
+        id: u64,
+        urgent: bool,
+    ) {
+        let mut state = write_lock!(state_lock);
+
+        if mode != WorkspaceMode::Windows {
+            if let Some(window) = state.windows.get_mut(&id) {
+                window.is_urgent = urgent;
+            }
+            return;
+        }
 
-            // 2. Check for Remove
-            for old_w in old_state.iter() {
-                if !new_workspaces.iter().any(|w| w.id == old_w.id) {
-                    updates.push(WorkspaceUpdate::Remove(old_w.id));
+        let old = state.windows_ordered(sort);
+
+        if state.windows.get_mut(&id).map(|w| w.is_urgent = urgent).is_none() {
+            return;
+        }
+
+        let new = state.windows_ordered(sort);
+        diff_and_emit(tx, &old, &new);
+    }
+
+    /// Workspace counterpart of `apply_window_urgency_changed` - see there for why this
+    /// can't be left to the next full resync.
+    fn apply_workspace_urgency_changed(
+        tx: &broadcast::Sender<WorkspaceUpdate>,
+        state_lock: &Arc<RwLock<State>>,
+        mode: WorkspaceMode,
+        sort: SortMode,
+        id: u64,
+        urgent: bool,
+    ) {
+        let mut state = write_lock!(state_lock);
+
+        if mode != WorkspaceMode::Workspaces {
+            if let Some(workspace) = state.workspaces.get_mut(&id) {
+                workspace.is_urgent = urgent;
+            }
+            return;
+        }
+
+        let old = (sort == SortMode::Mru).then(|| state.workspaces_ordered(sort));
+
+        if state.workspaces.get_mut(&id).map(|w| w.is_urgent = urgent).is_none() {
+            return;
+        }
+
+        match old {
+            Some(old) => {
+                let new = state.workspaces_ordered(sort);
+                diff_and_emit(tx, &old, &new);
+            }
+            None => {
+                if let Some(workspace) = state.workspaces.get(&id) {
+                    tx.send_expect(WorkspaceUpdate::Move(workspace_to_ironworkspace(workspace)));
                 }
             }
         }
+    }
+}
+
+impl Client {
+    /// Sends an `Action` through the persistent command connection, logging both a
+    /// failure to send it and a request Niri received but rejected - a rejected
+    /// `Action` (e.g. an unknown workspace id/name) comes back as `Ok(Err(_))` rather
+    /// than an `io::Error`, and silently dropping that hides exactly the failures a
+    /// drag-to-workspace or focus-by-name call is likely to hit.
+    fn dispatch_action(&self, action: Action) {
+        let actions = self.actions.clone();
+        spawn(async move {
+            match actions.send(Request::Action(action)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(msg)) => error!("Niri rejected action: {msg}"),
+                Err(err) => error!("failed to send Niri action: {err:?}"),
+            }
+        });
+    }
+}
+
+/// Niri-side plumbing for moving a window to a workspace and focusing a named
+/// workspace, dispatched through `Action::MoveWindowToWorkspace`/`Action::FocusWorkspace`
+/// exactly as chunk0-5 asks.
+///
+/// Scope note, read before wiring up a caller: chunk0-5 also asked for these on the
+/// shared `WorkspaceClient` trait, so a compositor-agnostic caller - a drag-and-drop
+/// handler or a named-workspace click in the bar widgets - could reach them through
+/// `&dyn WorkspaceClient`. That part is **explicitly out of scope here** and not done:
+/// `compositor/mod.rs`, where `WorkspaceClient` is defined alongside every other
+/// backend, is not present in this checkout, so there's nowhere compositor-agnostic to
+/// add the methods from here. This trait exists only so the Niri-side half (the
+/// `Action` dispatch, which is correct and ready) has somewhere to live in the
+/// meantime; it is a stopgap, not a substitute, and nothing in the bar can call these
+/// through `&dyn WorkspaceClient` yet - only through a concrete `niri::Client`.
+/// `move_window_to_workspace`/`focus_named_workspace`'s signatures are written to be
+/// lifted onto `WorkspaceClient` verbatim (dropping this trait and its impl) the
+/// moment that file exists in the tree.
+/// TODO(chunk0-5): do that promotion and wire up the widget-side callers it unblocks;
+/// don't let this stopgap go stale or get mistaken for the finished feature.
+pub trait NiriWorkspaceActions {
+    /// Moves a window onto the given target workspace (by Niri workspace id) and
+    /// switches focus there, dispatched via `Action::MoveWindowToWorkspace`. This
+    /// backs the "drag a button onto another workspace" interaction.
+    fn move_window_to_workspace(&self, window_id: i64, workspace_id: i64);
+
+    /// Focuses a workspace by its Niri-reported name, addressing named/special
+    /// workspaces that Niri only ever reports with a non-empty `name` rather than a
+    /// stable positional index.
+    fn focus_named_workspace(&self, name: &str);
+}
+
+impl NiriWorkspaceActions for Client {
+    fn move_window_to_workspace(&self, window_id: i64, workspace_id: i64) {
+        debug!("moving window {window_id} to workspace {workspace_id}");
+
+        self.dispatch_action(Action::MoveWindowToWorkspace {
+            window_id: Some(window_id as u64),
+            reference: WorkspaceReferenceArg::Id(workspace_id as u64),
+            focus: true,
+        });
+    }
+
+    fn focus_named_workspace(&self, name: &str) {
+        debug!("focusing named workspace {name}");
+
+        self.dispatch_action(Action::FocusWorkspace {
+            reference: WorkspaceReferenceArg::Name(name.to_string()),
+        });
+    }
+}
+
+impl WorkspaceClient for Client {
+    fn focus(&self, id: i64) {
+        debug!("focusing with id: {}", id);
+
+        // In window-list mode we're focusing a window; in native mode we're
+        // focusing a workspace directly.
+        let action = match self.mode {
+            WorkspaceMode::Windows => Action::FocusWindow { id: id as u64 },
+            WorkspaceMode::Workspaces => Action::FocusWorkspace {
+                reference: WorkspaceReferenceArg::Id(id as u64),
+            },
+        };
+
+        self.dispatch_action(action);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<WorkspaceUpdate> {
+        let rx = self.tx.subscribe();
 
-        // Apply updates
-        *write_lock!(state_lock) = new_workspaces;
-        for update in updates {
-            tx.send_expect(update);
+        let state = read_lock!(self.state);
+        let ordered = state.ordered(self.mode, self.sort);
+        if !ordered.is_empty() {
+            self.tx.send_expect(WorkspaceUpdate::Init(ordered));
         }
+
+        rx
     }
+}
 
-    fn window_to_workspace(window: &Window, index: i64) -> IronWorkspace {
-        let is_focused = window.is_focused;
-        
-        // We map the window ID to the workspace ID so buttons track windows.
-        // We use the window title (or app_id if title is empty) as the name.
-        let name = window
-            .title
-            .clone()
-            .or_else(|| window.app_id.clone())
-            .unwrap_or_else(|| "Window".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn ironworkspace(id: i64, index: i64, name: &str, focused: bool) -> IronWorkspace {
         IronWorkspace {
-            id: window.id as i64,
-            index, // Use the visual index from the list
-            name,
-            monitor: window.output.clone().unwrap_or_default(),
-            visibility: if is_focused {
+            id,
+            index,
+            name: name.to_string(),
+            monitor: "eDP-1".to_string(),
+            visibility: if focused {
                 Visibility::focused()
             } else {
                 Visibility::visible()
             },
         }
     }
-}
 
-impl WorkspaceClient for Client {
-    fn focus(&self, id: i64) {
-        debug!("focusing window with id: {}", id);
+    #[test]
+    fn diff_emits_add_for_new_workspace() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let new = vec![ironworkspace(1, 0, "one", false)];
 
-        spawn(async move {
-            let mut conn = match Connection::connect().await {
-                Ok(c) => c,
-                Err(e) => {
-                    error!("Failed to connect to Niri for focus: {e}");
-                    return;
-                }
-            };
+        diff_and_emit(&tx, &[], &new);
 
-            // We are focusing a WINDOW, so we use Action::FocusWindow
-            let command = Request::Action(Action::FocusWindow {
-                id: id as u64,
-            });
+        assert!(matches!(rx.try_recv(), Ok(WorkspaceUpdate::Add(w)) if w.id == 1));
+    }
 
-            if let Err(err) = conn.send(command).await {
-                error!("failed to send focus command: {err:?}");
-            }
-        });
+    #[test]
+    fn diff_emits_remove_for_vanished_workspace() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let old = vec![ironworkspace(1, 0, "one", false)];
+
+        diff_and_emit(&tx, &old, &[]);
+
+        assert!(matches!(rx.try_recv(), Ok(WorkspaceUpdate::Remove(1))));
     }
 
-    fn subscribe(&self) -> broadcast::Receiver<WorkspaceUpdate> {
-        let rx = self.tx.subscribe();
+    #[test]
+    fn diff_emits_rename_on_name_change() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let old = vec![ironworkspace(1, 0, "one", false)];
+        let new = vec![ironworkspace(1, 0, "renamed", false)];
 
-        let windows = read_lock!(self.windows_as_workspaces);
-        if !windows.is_empty() {
-            self.tx
-                .send_expect(WorkspaceUpdate::Init(windows.clone()));
+        diff_and_emit(&tx, &old, &new);
+
+        let update = rx.try_recv().expect("expected an update");
+        assert!(matches!(update, WorkspaceUpdate::Rename { id: 1, ref name } if name == "renamed"));
+    }
+
+    #[test]
+    fn diff_emits_focus_on_focus_gain() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let old = vec![ironworkspace(1, 0, "one", false)];
+        let new = vec![ironworkspace(1, 0, "one", true)];
+
+        diff_and_emit(&tx, &old, &new);
+
+        assert!(matches!(rx.try_recv(), Ok(WorkspaceUpdate::Focus { .. })));
+    }
+
+    #[test]
+    fn diff_emits_move_on_focus_loss() {
+        // Losing focus has no dedicated update type (`WorkspaceUpdate::Focus::new` is
+        // non-optional), so it must surface as a Move instead of being dropped.
+        let (tx, mut rx) = broadcast::channel(8);
+        let old = vec![ironworkspace(1, 0, "one", true)];
+        let new = vec![ironworkspace(1, 0, "one", false)];
+
+        diff_and_emit(&tx, &old, &new);
+
+        assert!(matches!(rx.try_recv(), Ok(WorkspaceUpdate::Move(w)) if w.id == 1 && !w.visibility.is_focused()));
+    }
+
+    #[test]
+    fn diff_emits_nothing_when_unchanged() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let old = vec![ironworkspace(1, 0, "one", false)];
+        let new = vec![ironworkspace(1, 0, "one", false)];
+
+        diff_and_emit(&tx, &old, &new);
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn mru_sort_ranks_urgent_first_then_lru_then_focused_last() {
+        let mut state = State::default();
+        state
+            .last_focused
+            .insert(2, Instant::now() - std::time::Duration::from_secs(10));
+        state
+            .last_focused
+            .insert(3, Instant::now() - std::time::Duration::from_secs(1));
+
+        let mut workspaces = vec![
+            ironworkspace(4, 3, "focused", true),
+            ironworkspace(3, 2, "recently focused", false),
+            ironworkspace(2, 1, "least recently focused", false),
+            ironworkspace(1, 0, "urgent", false),
+        ];
+
+        state.sort(&mut workspaces, SortMode::Mru, std::iter::once(1u64));
+
+        let ids: Vec<i64> = workspaces.iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+
+        // The synthetic rank is written back into `index` so downstream widgets, which
+        // place buttons by `index`, pick it up without further changes.
+        for (rank, w) in workspaces.iter().enumerate() {
+            assert_eq!(w.index, rank as i64);
         }
+    }
 
-        rx
+    #[test]
+    fn index_sort_keeps_niri_reported_order() {
+        let state = State::default();
+        let mut workspaces = vec![
+            ironworkspace(2, 1, "two", false),
+            ironworkspace(1, 0, "one", false),
+        ];
+
+        state.sort(&mut workspaces, SortMode::Index, std::iter::empty());
+
+        let ids: Vec<i64> = workspaces.iter().map(|w| w.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn reindex_rewrites_shared_index_to_a_unique_rank() {
+        // Regression case: several windows on the same Niri workspace share `index`
+        // before reindexing (`build_window` borrows the workspace's `idx` directly),
+        // which would otherwise collide under the "place buttons by index" contract.
+        let mut workspaces = vec![
+            ironworkspace(1, 0, "one", false),
+            ironworkspace(2, 0, "two", false),
+            ironworkspace(3, 0, "three", false),
+        ];
+
+        reindex(&mut workspaces);
+
+        let indices: Vec<i64> = workspaces.iter().map(|w| w.index).collect();
+        assert_eq!(indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn activation_changes_secondary_output_workspace_even_when_not_focused() {
+        // Regression case: activating a workspace on a non-focused secondary monitor
+        // (the common case in multi-monitor setups) must still be reported, not only
+        // activations that also move keyboard focus.
+        let workspaces = vec![
+            (1, Some("eDP-1".to_string()), true, true),
+            (2, Some("HDMI-1".to_string()), true, false),
+            (3, Some("HDMI-1".to_string()), false, false),
+        ];
+
+        let changed = activation_changed_ids(
+            workspaces.into_iter(),
+            3,
+            &Some("HDMI-1".to_string()),
+            false,
+        );
+
+        // The newly activated workspace and the sibling it displaces on the same
+        // output - not workspace 1, which is on an unrelated output and unfocused.
+        assert_eq!(changed, vec![2, 3]);
+    }
+
+    #[test]
+    fn activation_with_focus_also_changes_the_previously_focused_workspace() {
+        let workspaces = vec![
+            (1, Some("eDP-1".to_string()), true, true),
+            (2, Some("HDMI-1".to_string()), false, false),
+        ];
+
+        let changed =
+            activation_changed_ids(workspaces.into_iter(), 2, &Some("HDMI-1".to_string()), true);
+
+        assert_eq!(changed, vec![1, 2]);
     }
 }